@@ -2,6 +2,8 @@ extern crate num;
 extern crate image;
 extern crate crossbeam;
 extern crate rayon;
+extern crate rand;
+extern crate thiserror;
 
 use num::Complex;
 use image::ColorType;
@@ -10,25 +12,190 @@ use std::str::FromStr;
 use std::fs::File;
 use std::io::Write;
 use rayon::prelude::*;
+use rand::Rng;
+use thiserror::Error;
 
-fn escape_time(c: Complex<f64>, limit: u32) -> Option<u32> {
-    let mut z = Complex { re: 0.0, im: 0.0 };
+/// The family of escape-time fractals this tool can render, selected on the
+/// command line and threaded through every render backend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FractalKind {
+    Mandelbrot,
+    MandelbrotN(u32),
+    BurningShip,
+    Julia { c: Complex<f64> },
+}
+
+impl FromStr for FractalKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        if lower == "mandelbrot" {
+            Ok(FractalKind::Mandelbrot)
+        } else if lower == "burningship" {
+            Ok(FractalKind::BurningShip)
+        } else if let Some(power) = lower.strip_prefix("mandelbrot") {
+            power.parse::<u32>()
+                .map(FractalKind::MandelbrotN)
+                .map_err(|_| format!("invalid power in fractal kind: '{}'", s))
+        } else if let Some(param) = lower.strip_prefix("julia:") {
+            parse_complex(param)
+                .map(|c| FractalKind::Julia { c })
+                .map_err(|reason| format!("invalid julia parameter: '{}': {}", param, reason))
+        } else {
+            Err(format!("unknown fractal kind: '{}'", s))
+        }
+    }
+}
+
+#[test]
+fn test_fractal_kind_from_str() {
+    assert_eq!(FractalKind::from_str("mandelbrot"), Ok(FractalKind::Mandelbrot));
+    assert_eq!(FractalKind::from_str("mandelbrot3"), Ok(FractalKind::MandelbrotN(3)));
+    assert_eq!(FractalKind::from_str("burningship"), Ok(FractalKind::BurningShip));
+    assert_eq!(FractalKind::from_str("julia:-0.4,0.6"),
+               Ok(FractalKind::Julia { c: Complex { re: -0.4, im: 0.6 } }));
+    assert!(FractalKind::from_str("nonsense").is_err());
+}
+
+/// Bailout radius used to detect escape. Raised well past the classic 2.0
+/// (i.e. `norm_sqr() > 4.0`) so the continuous iteration count used for
+/// smooth coloring has room to converge before the orbit is cut off.
+const BAILOUT_NORM_SQR: f64 = (1u32 << 16) as f64;
+
+/// Cheap membership tests for the main cardioid and the period-2 bulb of the
+/// standard Mandelbrot set, letting `escape_time` skip iterating points it
+/// already knows never escape.
+fn in_known_interior(point: Complex<f64>) -> bool {
+    let (x, y) = (point.re, point.im);
+    let q = (x - 0.25) * (x - 0.25) + y * y;
+    if q * (q + (x - 0.25)) <= 0.25 * y * y {
+        return true;
+    }
+    (x + 1.0) * (x + 1.0) + y * y <= 1.0 / 16.0
+}
+
+#[test]
+fn test_in_known_interior() {
+    // Origin is deep inside the main cardioid.
+    assert!(in_known_interior(Complex { re: 0.0, im: 0.0 }));
+    // Center of the period-2 bulb.
+    assert!(in_known_interior(Complex { re: -1.0, im: 0.0 }));
+    // Well outside the set entirely.
+    assert!(!in_known_interior(Complex { re: 1.0, im: 1.0 }));
+    // Just past the tip of the cardioid on the real axis.
+    assert!(!in_known_interior(Complex { re: 0.4, im: 0.0 }));
+}
+
+/// Runs the escape-time iteration for `kind` at `point`. Returns the
+/// iteration at which the orbit escaped together with the final `z`, so
+/// callers can derive either a discrete count or a smooth (continuous)
+/// iteration count from it.
+fn escape_time(kind: &FractalKind, point: Complex<f64>, limit: u32) -> Option<(u32, Complex<f64>)> {
+    if let FractalKind::Mandelbrot = *kind {
+        if in_known_interior(point) {
+            return None;
+        }
+    }
+
+    let (mut z, c) = match *kind {
+        FractalKind::Julia { c } => (point, c),
+        _ => (Complex { re: 0.0, im: 0.0 }, point),
+    };
     for i in 0..limit {
-        z = z * z + c;
-        if z.norm_sqr() > 4.0 {
-            return Some(i);
+        z = match *kind {
+            FractalKind::Mandelbrot | FractalKind::Julia { .. } => z * z + c,
+            FractalKind::MandelbrotN(n) => z.powu(n) + c,
+            FractalKind::BurningShip => {
+                let folded = Complex { re: z.re.abs(), im: z.im.abs() };
+                folded * folded + c
+            }
+        };
+        if z.norm_sqr() > BAILOUT_NORM_SQR {
+            return Some((i, z));
         }
     }
     None
 }
 
-fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
+/// Continuous (fractional) iteration count, which removes the banding you
+/// get from coloring by the integer escape count alone.
+fn smooth_count(i: u32, z: Complex<f64>) -> f64 {
+    i as f64 + 1.0 - (z.norm().ln().ln() / std::f64::consts::LN_2)
+}
+
+/// A palette control color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rgb(u8, u8, u8);
+
+/// Control colors the smooth iteration count is interpolated across,
+/// `period` counts per pair of neighbouring colors, so the whole palette
+/// repeats every `period * (PALETTE.len() - 1)` counts.
+const PALETTE: [Rgb; 6] = [
+    Rgb(0, 7, 100),
+    Rgb(32, 107, 203),
+    Rgb(237, 255, 255),
+    Rgb(255, 170, 0),
+    Rgb(0, 2, 0),
+    Rgb(0, 7, 100),
+];
+
+fn lerp_channel(a: u8, b: u8, t: f64) -> u8 {
+    (a as f64 + (b as f64 - a as f64) * t).round() as u8
+}
+
+fn palette_color(mu: f64, period: f64) -> Rgb {
+    let segments = PALETTE.len() - 1;
+    let t = (mu / period).rem_euclid(segments as f64);
+    let index = t as usize;
+    let frac = t - index as f64;
+    let Rgb(r0, g0, b0) = PALETTE[index];
+    let Rgb(r1, g1, b1) = PALETTE[index + 1];
+    Rgb(lerp_channel(r0, r1, frac), lerp_channel(g0, g1, frac), lerp_channel(b0, b1, frac))
+}
+
+#[test]
+fn test_lerp_channel() {
+    assert_eq!(lerp_channel(0, 100, 0.0), 0);
+    assert_eq!(lerp_channel(0, 100, 1.0), 100);
+    assert_eq!(lerp_channel(0, 100, 0.5), 50);
+}
+
+#[test]
+fn test_palette_color_lands_on_control_colors() {
+    assert_eq!(palette_color(0.0, 64.0), PALETTE[0]);
+    assert_eq!(palette_color(64.0, 64.0), PALETTE[1]);
+    // mu cycles every `period * (PALETTE.len() - 1)` counts.
+    assert_eq!(palette_color(64.0 * (PALETTE.len() - 1) as f64, 64.0), PALETTE[0]);
+}
+
+/// Whether to render a single grayscale byte per pixel or a smooth-colored
+/// RGB triple, and (for color) how many iterations span the step from one
+/// palette control color to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ColorMode {
+    Gray,
+    Color { period: f64 },
+}
+
+impl ColorMode {
+    fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            ColorMode::Gray => 1,
+            ColorMode::Color { .. } => 3,
+        }
+    }
+}
+
+const DEFAULT_PALETTE_PERIOD: f64 = 64.0;
+
+fn parse_pair<T: FromStr>(s: &str, separator: char) -> Result<(T, T), String> {
     match s.find(separator) {
-        None => None,
+        None => Err(format!("'{}' has no '{}' separator", s, separator)),
         Some(index) => {
             match (T::from_str(&s[..index]), T::from_str(&s[index+1..])) {
-                (Ok(l), Ok(r)) => Some((l, r)),
-                _ => None
+                (Ok(l), Ok(r)) => Ok((l, r)),
+                _ => Err(format!("could not parse '{}' as a pair of numbers separated by '{}'", s, separator))
             }
         }
     }
@@ -36,26 +203,23 @@ fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
 
 #[test]
 fn test_parse_pair(){
-    assert_eq!(parse_pair::<i32>("", ','), None);
-    assert_eq!(parse_pair::<i32>("10,", ','), None);
-    assert_eq!(parse_pair::<i32>(",10", ','), None);
-    assert_eq!(parse_pair::<i32>("10,20", ','), Some((10, 20)));
-    assert_eq!(parse_pair::<i32>("10,20xy", ','), None);
-    assert_eq!(parse_pair::<f64>("0.5x", 'x'), None);
-    assert_eq!(parse_pair::<f64>("0.5x1.5", 'x'), Some((0.5, 1.5)));
+    assert!(parse_pair::<i32>("", ',').is_err());
+    assert!(parse_pair::<i32>("10,", ',').is_err());
+    assert!(parse_pair::<i32>(",10", ',').is_err());
+    assert_eq!(parse_pair::<i32>("10,20", ','), Ok((10, 20)));
+    assert!(parse_pair::<i32>("10,20xy", ',').is_err());
+    assert!(parse_pair::<f64>("0.5x", 'x').is_err());
+    assert_eq!(parse_pair::<f64>("0.5x1.5", 'x'), Ok((0.5, 1.5)));
 }
 
-fn parse_complex(s: &str) -> Option<Complex<f64>> {
-    match parse_pair(s, ',') {
-        Some((re, im)) => Some(Complex {re, im}),
-        None => None
-    }
+fn parse_complex(s: &str) -> Result<Complex<f64>, String> {
+    parse_pair(s, ',').map(|(re, im)| Complex { re, im })
 }
 
 #[test]
 fn test_parse_complex() {
-    assert_eq!(parse_complex("1.25,-0.0625"), Some(Complex { re: 1.25, im: -0.0625}));
-    assert_eq!(parse_complex(",-0.0625"), None);
+    assert_eq!(parse_complex("1.25,-0.0625"), Ok(Complex { re: 1.25, im: -0.0625}));
+    assert!(parse_complex(",-0.0625").is_err());
 }
 
 fn pixel_to_point(
@@ -78,39 +242,95 @@ fn test_pixel_to_point() {
     Complex{ re: -0.5, im: -0.5})
 }
 
+/// Inverse of `pixel_to_point`: maps a point in the complex plane back to
+/// the pixel it falls in, or `None` if it falls outside `bounds`.
+fn point_to_pixel(
+    bounds: (usize, usize),
+    point: Complex<f64>,
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>) -> Option<(usize, usize)> {
+    let (width, height) = (lower_right.re - upper_left.re, upper_left.im - lower_right.im);
+    let column = (point.re - upper_left.re) * bounds.0 as f64 / width;
+    let row = (upper_left.im - point.im) * bounds.1 as f64 / height;
+    if column < 0.0 || row < 0.0 {
+        return None;
+    }
+    let (column, row) = (column as usize, row as usize);
+    if column >= bounds.0 || row >= bounds.1 {
+        None
+    } else {
+        Some((column, row))
+    }
+}
+
+#[test]
+fn test_point_to_pixel() {
+    assert_eq!(point_to_pixel((100, 100), Complex { re: -0.5, im: -0.5 },
+                              Complex { re: -1.0, im: 1.0 },
+                              Complex { re: 1.0, im: -1.0 }),
+    Some((25, 75)));
+    assert_eq!(point_to_pixel((100, 100), Complex { re: -5.0, im: -5.0 },
+                              Complex { re: -1.0, im: 1.0 },
+                              Complex { re: 1.0, im: -1.0 }),
+    None);
+}
+
+/// Writes the color for one escape-time result into `pixels` at `offset`,
+/// shared by every render path so the gray/color rules stay in one place.
+fn color_pixel(pixels: &mut [u8], offset: usize, bpp: usize, mode: &ColorMode, escape: Option<(u32, Complex<f64>)>) {
+    match (escape, *mode) {
+        (None, _) => {
+            for b in pixels[offset .. offset + bpp].iter_mut() { *b = 0; }
+        }
+        (Some((count, _)), ColorMode::Gray) => {
+            pixels[offset] = 255 - count as u8;
+        }
+        (Some((count, z)), ColorMode::Color { period }) => {
+            let Rgb(r, g, b) = palette_color(smooth_count(count, z), period);
+            pixels[offset] = r;
+            pixels[offset + 1] = g;
+            pixels[offset + 2] = b;
+        }
+    }
+}
+
 fn render(pixels: &mut [u8],
     bounds: (usize, usize),
     upper_left: Complex<f64>,
-    lower_right: Complex<f64>)
+    lower_right: Complex<f64>,
+    kind: &FractalKind,
+    mode: &ColorMode)
 {
+    let bpp = mode.bytes_per_pixel();
     for row in 0 .. bounds.1 {
         for column in 0 .. bounds.0 {
             let point = pixel_to_point(bounds, (column, row), upper_left, lower_right);
-            pixels[row * bounds.0 + column] = match escape_time(point, 255) {
-                None => 0,
-                Some(count) => 255 - count as u8
-            };
+            let offset = (row * bounds.0 + column) * bpp;
+            color_pixel(pixels, offset, bpp, mode, escape_time(kind, point, 255));
         }
     }
 }
 fn render_by_crossbeam(pixels: &mut [u8],
                    bounds: (usize, usize),
                    upper_left: Complex<f64>,
-                   lower_right: Complex<f64>)
+                   lower_right: Complex<f64>,
+                   kind: &FractalKind,
+                   mode: &ColorMode)
 {
+    let bpp = mode.bytes_per_pixel();
     let threads = 8;
     let rows_per_band = bounds.1 / threads + 1;
-    let bands: Vec<&mut [u8]> = pixels.chunks_mut(rows_per_band * bounds.0).collect();
+    let bands: Vec<&mut [u8]> = pixels.chunks_mut(rows_per_band * bounds.0 * bpp).collect();
     crossbeam::scope(|spawner| {
         for (i, band) in bands.into_iter().enumerate() {
             let top = rows_per_band * i;
-            let height = band.len() / bounds.0;
+            let height = band.len() / (bounds.0 * bpp);
             let band_bounds = (bounds.0, height);
             let band_upper_left = pixel_to_point(bounds, (0, top), upper_left, lower_right);
             let band_lower_right = pixel_to_point(bounds, (bounds.0, top + height), upper_left, lower_right);
 
             spawner.spawn(move |_| {
-                render(band, band_bounds, band_upper_left, band_lower_right);
+                render(band, band_bounds, band_upper_left, band_lower_right, kind, mode);
             });
 
         }
@@ -119,82 +339,833 @@ fn render_by_crossbeam(pixels: &mut [u8],
 fn render_by_rayon(pixels: &mut [u8],
                    bounds: (usize, usize),
                    upper_left: Complex<f64>,
-                   lower_right: Complex<f64>)
+                   lower_right: Complex<f64>,
+                   kind: &FractalKind,
+                   mode: &ColorMode)
 {
-    let bands: Vec<(usize, &mut[u8])> = pixels.chunks_mut(bounds.0).enumerate().collect();
+    let bpp = mode.bytes_per_pixel();
+    let bands: Vec<(usize, &mut[u8])> = pixels.chunks_mut(bounds.0 * bpp).enumerate().collect();
     bands.into_par_iter()
         .for_each(|(i, band)| {
             let top = i;
             let band_bounds = (bounds.0, 1);
             let band_upper_left = pixel_to_point(bounds, (0, top), upper_left, lower_right);
             let band_lower_right = pixel_to_point(bounds, (bounds.0, top + 1), upper_left, lower_right);
-            render(band, band_bounds, band_upper_left, band_lower_right);
+            render(band, band_bounds, band_upper_left, band_lower_right, kind, mode);
+        });
+}
+
+/// At or below this size a tile is rendered pixel-by-pixel rather than subdivided
+/// further; subdividing smaller tiles costs more in boundary sampling than
+/// it would ever save.
+const ADAPTIVE_MIN_TILE: usize = 8;
+
+/// The pixel coordinates making up the border of a `width` by `height`
+/// rectangle whose top-left corner is `(left, top)`.
+fn border_pixels(left: usize, top: usize, width: usize, height: usize) -> Vec<(usize, usize)> {
+    let mut border = Vec::with_capacity(2 * (width + height));
+    for column in left .. left + width {
+        border.push((column, top));
+        border.push((column, top + height - 1));
+    }
+    for row in top .. top + height {
+        border.push((left, row));
+        border.push((left + width - 1, row));
+    }
+    border
+}
+
+fn render_rect(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    rect: (usize, usize, usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    kind: &FractalKind,
+    mode: &ColorMode)
+{
+    let bpp = mode.bytes_per_pixel();
+    let (left, top, width, height) = rect;
+    for row in top .. top + height {
+        for column in left .. left + width {
+            let point = pixel_to_point(bounds, (column, row), upper_left, lower_right);
+            let offset = (row * bounds.0 + column) * bpp;
+            color_pixel(pixels, offset, bpp, mode, escape_time(kind, point, 255));
+        }
+    }
+}
+
+fn fill_rect(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    rect: (usize, usize, usize, usize),
+    escape: Option<(u32, Complex<f64>)>,
+    mode: &ColorMode)
+{
+    let bpp = mode.bytes_per_pixel();
+    let (left, top, width, height) = rect;
+    for row in top .. top + height {
+        for column in left .. left + width {
+            let offset = (row * bounds.0 + column) * bpp;
+            color_pixel(pixels, offset, bpp, mode, escape);
+        }
+    }
+}
+
+/// Mariani-Silver subdivision: samples only the border of `rect`, and if
+/// every border pixel shares one escape value, flood-fills the whole
+/// rectangle with that value instead of iterating its interior. Rectangles
+/// that are already small are rendered pixel by pixel; rectangles whose
+/// border disagrees are subdivided into quadrants.
+///
+/// A uniform discrete escape count is only safe to flood-fill as-is when
+/// every pixel in the rectangle never escapes (`color_pixel` colors those
+/// black regardless of mode) or when coloring by the discrete count itself
+/// (`ColorMode::Gray`). In `ColorMode::Color`, pixels that share a discrete
+/// count can still have different continuous `mu` values (see
+/// `smooth_count`), so a uniform *escaping* border is rendered pixel by
+/// pixel instead of flood-filled.
+fn subdivide(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    rect: (usize, usize, usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    kind: &FractalKind,
+    mode: &ColorMode)
+{
+    let (left, top, width, height) = rect;
+    if width == 0 || height == 0 {
+        return;
+    }
+    if width <= ADAPTIVE_MIN_TILE || height <= ADAPTIVE_MIN_TILE {
+        render_rect(pixels, bounds, rect, upper_left, lower_right, kind, mode);
+        return;
+    }
+
+    let mut uniform: Option<(Option<u32>, Option<(u32, Complex<f64>)>)> = None;
+    let mut is_uniform = true;
+    for (column, row) in border_pixels(left, top, width, height) {
+        let point = pixel_to_point(bounds, (column, row), upper_left, lower_right);
+        let escape = escape_time(kind, point, 255);
+        let key = escape.map(|(count, _)| count);
+        match &uniform {
+            None => uniform = Some((key, escape)),
+            Some((existing_key, _)) if *existing_key != key => is_uniform = false,
+            _ => {}
+        }
+    }
+
+    let can_flood_fill = |key: Option<u32>| key.is_none() || matches!(mode, ColorMode::Gray);
+    match uniform {
+        Some((key, escape)) if is_uniform && can_flood_fill(key) => fill_rect(pixels, bounds, rect, escape, mode),
+        _ => {
+            let half_w = width / 2;
+            let half_h = height / 2;
+            subdivide(pixels, bounds, (left, top, half_w, half_h), upper_left, lower_right, kind, mode);
+            subdivide(pixels, bounds, (left + half_w, top, width - half_w, half_h), upper_left, lower_right, kind, mode);
+            subdivide(pixels, bounds, (left, top + half_h, half_w, height - half_h), upper_left, lower_right, kind, mode);
+            subdivide(pixels, bounds, (left + half_w, top + half_h, width - half_w, height - half_h), upper_left, lower_right, kind, mode);
+        }
+    }
+}
+
+fn render_adaptive(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    kind: &FractalKind,
+    mode: &ColorMode)
+{
+    subdivide(pixels, bounds, (0, 0, bounds.0, bounds.1), upper_left, lower_right, kind, mode);
+}
+
+#[test]
+fn test_adaptive_matches_non_adaptive_in_color_mode() {
+    // Large enough to trigger several levels of subdivision (including
+    // uniform escaping borders that must NOT be flood-filled under smooth
+    // coloring), but still small enough to run quickly as a unit test.
+    let bounds = (64, 64);
+    let upper_left = Complex { re: -2.0, im: 1.0 };
+    let lower_right = Complex { re: 0.6, im: -1.0 };
+    let kind = FractalKind::Mandelbrot;
+    let mode = ColorMode::Color { period: DEFAULT_PALETTE_PERIOD };
+    let bpp = mode.bytes_per_pixel();
+
+    let mut plain = vec![0u8; bounds.0 * bounds.1 * bpp];
+    render(&mut plain, bounds, upper_left, lower_right, &kind, &mode);
+
+    let mut adaptive = vec![0u8; bounds.0 * bounds.1 * bpp];
+    render_adaptive(&mut adaptive, bounds, upper_left, lower_right, &kind, &mode);
+
+    assert_eq!(plain, adaptive);
+}
+
+#[test]
+fn test_adaptive_matches_non_adaptive_in_gray_mode() {
+    let bounds = (64, 64);
+    let upper_left = Complex { re: -2.0, im: 1.0 };
+    let lower_right = Complex { re: 0.6, im: -1.0 };
+    let kind = FractalKind::Mandelbrot;
+    let mode = ColorMode::Gray;
+    let bpp = mode.bytes_per_pixel();
+
+    let mut plain = vec![0u8; bounds.0 * bounds.1 * bpp];
+    render(&mut plain, bounds, upper_left, lower_right, &kind, &mode);
+
+    let mut adaptive = vec![0u8; bounds.0 * bounds.1 * bpp];
+    render_adaptive(&mut adaptive, bounds, upper_left, lower_right, &kind, &mode);
+
+    assert_eq!(plain, adaptive);
+}
+
+/// Number of rows per rayon work unit when `--adaptive` is selected. Needs
+/// to be large enough that the Mariani-Silver subdivision above has room to
+/// skip real work; a single-row band (as the non-adaptive rayon backend
+/// uses) would never have a boundary worth sampling.
+const ADAPTIVE_TILE_ROWS: usize = 32;
+
+fn render_by_rayon_adaptive(pixels: &mut [u8],
+                   bounds: (usize, usize),
+                   upper_left: Complex<f64>,
+                   lower_right: Complex<f64>,
+                   kind: &FractalKind,
+                   mode: &ColorMode)
+{
+    let bpp = mode.bytes_per_pixel();
+    let bands: Vec<(usize, &mut [u8])> = pixels.chunks_mut(ADAPTIVE_TILE_ROWS * bounds.0 * bpp).enumerate().collect();
+    bands.into_par_iter()
+        .for_each(|(i, band)| {
+            let top = ADAPTIVE_TILE_ROWS * i;
+            let height = band.len() / (bounds.0 * bpp);
+            let band_bounds = (bounds.0, height);
+            let band_upper_left = pixel_to_point(bounds, (0, top), upper_left, lower_right);
+            let band_lower_right = pixel_to_point(bounds, (bounds.0, top + height), upper_left, lower_right);
+            render_adaptive(band, band_bounds, band_upper_left, band_lower_right, kind, mode);
         });
 }
 
-fn write_image(filename: &str, pixels: &[u8], bounds: (usize, usize)) -> Result<(), std::io::Error>
+/// Default sample and iteration counts for the buddhabrot/nebulabrot
+/// backends, chosen to give a reasonably dense histogram without an
+/// unreasonable runtime.
+const BUDDHABROT_SAMPLES: u32 = 2_000_000;
+const BUDDHABROT_LIMIT: u32 = 1000;
+
+/// Draws `samples` random points `c`, replays the orbit of every one whose
+/// `z = z*z + c` iteration escapes within `limit` steps, and tallies how
+/// often each pixel is visited. Points that never escape (interior points)
+/// contribute nothing, which is what gives the buddhabrot its characteristic
+/// ghostly shape instead of the usual silhouette.
+fn buddhabrot_histogram(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    samples: u32,
+    limit: u32) -> Vec<u32>
+{
+    let workers = 8;
+    (0..workers)
+        .into_par_iter()
+        .map(|worker| {
+            let samples_per_worker = samples / workers + if worker < samples % workers { 1 } else { 0 };
+            let mut histogram = vec![0u32; bounds.0 * bounds.1];
+            let mut rng = rand::thread_rng();
+            for _ in 0..samples_per_worker {
+                let c = Complex {
+                    re: rng.gen_range(upper_left.re..lower_right.re),
+                    im: rng.gen_range(lower_right.im..upper_left.im),
+                };
+                let mut z = Complex { re: 0.0, im: 0.0 };
+                let mut orbit = Vec::with_capacity(limit as usize);
+                let mut escaped = false;
+                for _ in 0..limit {
+                    z = z * z + c;
+                    orbit.push(z);
+                    if z.norm_sqr() > 4.0 {
+                        escaped = true;
+                        break;
+                    }
+                }
+                if escaped {
+                    for point in &orbit {
+                        if let Some((column, row)) = point_to_pixel(bounds, *point, upper_left, lower_right) {
+                            histogram[row * bounds.0 + column] += 1;
+                        }
+                    }
+                }
+            }
+            histogram
+        })
+        .reduce(
+            || vec![0u32; bounds.0 * bounds.1],
+            |mut acc, chunk| {
+                for (a, c) in acc.iter_mut().zip(chunk.iter()) {
+                    *a += c;
+                }
+                acc
+            })
+}
+
+/// Normalizes a histogram into 8-bit intensities. A square-root (gamma)
+/// curve is used instead of a plain linear scale so that the faint,
+/// rarely-visited orbits stay visible next to the brightest pixels.
+fn histogram_to_pixels(histogram: &[u32]) -> Vec<u8> {
+    let max = histogram.iter().cloned().max().unwrap_or(0).max(1) as f64;
+    histogram.iter()
+        .map(|&count| ((count as f64 / max).sqrt() * 255.0).round() as u8)
+        .collect()
+}
+
+#[test]
+fn test_histogram_to_pixels() {
+    assert_eq!(histogram_to_pixels(&[0, 0, 0]), vec![0, 0, 0]);
+    assert_eq!(histogram_to_pixels(&[0, 100, 100]), vec![0, 255, 255]);
+    // A quarter of the max count still shows up at half brightness thanks
+    // to the gamma curve, rather than being crushed toward black.
+    assert_eq!(histogram_to_pixels(&[25, 100]), vec![128, 255]);
+}
+
+#[test]
+fn test_buddhabrot_histogram_covers_every_pixel() {
+    let bounds = (8, 8);
+    let histogram = buddhabrot_histogram(
+        bounds,
+        Complex { re: -2.0, im: 1.2 },
+        Complex { re: 0.6, im: -1.2 },
+        2000,
+        50);
+    assert_eq!(histogram.len(), bounds.0 * bounds.1);
+}
+
+fn render_buddhabrot(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    samples: u32,
+    limit: u32) -> Vec<u8>
+{
+    histogram_to_pixels(&buddhabrot_histogram(bounds, upper_left, lower_right, samples, limit))
+}
+
+/// Renders a three-channel "Nebulabrot" image by running the buddhabrot
+/// accumulation once per channel, each with its own iteration limit, and
+/// interleaving the normalized histograms into RGB triples.
+fn render_nebulabrot(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    samples: u32,
+    limits: (u32, u32, u32)) -> Vec<u8>
+{
+    let red = histogram_to_pixels(&buddhabrot_histogram(bounds, upper_left, lower_right, samples, limits.0));
+    let green = histogram_to_pixels(&buddhabrot_histogram(bounds, upper_left, lower_right, samples, limits.1));
+    let blue = histogram_to_pixels(&buddhabrot_histogram(bounds, upper_left, lower_right, samples, limits.2));
+
+    let mut pixels = vec![0u8; bounds.0 * bounds.1 * 3];
+    for i in 0 .. bounds.0 * bounds.1 {
+        pixels[i * 3] = red[i];
+        pixels[i * 3 + 1] = green[i];
+        pixels[i * 3 + 2] = blue[i];
+    }
+    pixels
+}
+
+/// Output container format, chosen from the output filename's extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Png,
+    Pnm,
+    Qoi,
+}
+
+impl OutputFormat {
+    fn from_filename(filename: &str) -> Result<OutputFormat, String> {
+        match filename.rsplit('.').next().unwrap_or("").to_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "ppm" | "pgm" => Ok(OutputFormat::Pnm),
+            "qoi" => Ok(OutputFormat::Qoi),
+            ext => Err(format!("unrecognized output extension: '.{}'", ext)),
+        }
+    }
+}
+
+fn write_image(filename: &str, pixels: &[u8], bounds: (usize, usize), mode: &ColorMode) -> Result<(), std::io::Error>
+{
+    match OutputFormat::from_filename(filename) {
+        Ok(OutputFormat::Png) => write_png(filename, pixels, bounds, mode),
+        Ok(OutputFormat::Pnm) => write_pnm(filename, pixels, bounds, mode),
+        Ok(OutputFormat::Qoi) => write_qoi(filename, pixels, bounds, mode),
+        Err(msg) => {
+            writeln!(std::io::stderr(), "{}, defaulting to PNG", msg).unwrap();
+            write_png(filename, pixels, bounds, mode)
+        }
+    }
+}
+
+fn write_png(filename: &str, pixels: &[u8], bounds: (usize, usize), mode: &ColorMode) -> Result<(), std::io::Error>
 {
     let output = File::create(filename)?;
 
+    let color_type = match *mode {
+        ColorMode::Gray => ColorType::Gray(8),
+        ColorMode::Color { .. } => ColorType::RGB(8),
+    };
     let encoder = PNGEncoder::new(output);
-    encoder.encode(&pixels, bounds.0 as u32, bounds.1 as u32, ColorType::Gray(8))?;
+    encoder.encode(&pixels, bounds.0 as u32, bounds.1 as u32, color_type)?;
 
     Ok(())
 }
 
-fn help(args: Vec<String>){
-    writeln!(std::io::stderr(), "Usage: mandelbrot FILE PIXELS UPPERLEFT LOWERRIGHT RENDERMETHOD").unwrap();
-    writeln!(std::io::stderr(), "Example: {} mandel.png 1280x960 -2.0,1 0.6,-1 rayon", args[0]).unwrap();
+/// Dependency-free binary PNM writer: P5 (PGM) for grayscale, P6 (PPM) for
+/// RGB. No compression, just the plain header followed by raw samples.
+fn write_pnm(filename: &str, pixels: &[u8], bounds: (usize, usize), mode: &ColorMode) -> Result<(), std::io::Error>
+{
+    let mut output = File::create(filename)?;
+    let magic = match *mode {
+        ColorMode::Gray => "P5",
+        ColorMode::Color { .. } => "P6",
+    };
+    write!(output, "{}\n{} {}\n255\n", magic, bounds.0, bounds.1)?;
+    output.write_all(pixels)?;
+
+    Ok(())
 }
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
+/// Widens grayscale or RGB pixels to RGBA (opaque) for the QOI encoder,
+/// which only works on 4-channel data.
+fn to_rgba(pixels: &[u8], mode: &ColorMode) -> Vec<u8> {
+    match *mode {
+        ColorMode::Gray => pixels.iter().flat_map(|&g| vec![g, g, g, 255]).collect(),
+        ColorMode::Color { .. } => pixels.chunks(3).flat_map(|c| vec![c[0], c[1], c[2], 255]).collect(),
+    }
+}
+
+fn write_qoi(filename: &str, pixels: &[u8], bounds: (usize, usize), mode: &ColorMode) -> Result<(), std::io::Error>
+{
+    let rgba = to_rgba(pixels, mode);
+    let encoded = qoi_encode(&rgba, bounds);
+    let mut output = File::create(filename)?;
+    output.write_all(&encoded)?;
+
+    Ok(())
+}
+
+fn qoi_hash(px: [u8; 4]) -> usize {
+    (px[0] as usize * 3 + px[1] as usize * 5 + px[2] as usize * 7 + px[3] as usize * 11) % 64
+}
+
+/// Lossless QOI encoder (https://qoiformat.org/qoi-specification.pdf):
+/// a 14-byte header followed by a stream of chunks (run, index-table hit,
+/// small per-channel delta, or literal), terminated by the fixed
+/// `0x00 * 7, 0x01` end marker.
+fn qoi_encode(rgba: &[u8], bounds: (usize, usize)) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rgba.len());
+    out.extend_from_slice(b"qoif");
+    out.extend_from_slice(&(bounds.0 as u32).to_be_bytes());
+    out.extend_from_slice(&(bounds.1 as u32).to_be_bytes());
+    out.push(4); // channels: RGBA
+    out.push(0); // colorspace: sRGB with linear alpha
 
-    let render_method = {
-        match args.len() {
-            5 => {
-                writeln!(std::io::stderr(), "selected render method is rayon").unwrap();
-                render_by_rayon
+    let mut table = [[0u8; 4]; 64];
+    let mut prev = [0u8, 0, 0, 255];
+    let mut run: u8 = 0;
+
+    let pixel_count = rgba.len() / 4;
+    for i in 0 .. pixel_count {
+        let px = [rgba[i * 4], rgba[i * 4 + 1], rgba[i * 4 + 2], rgba[i * 4 + 3]];
+
+        if px == prev {
+            run += 1;
+            if run == 62 || i == pixel_count - 1 {
+                out.push(0b11_000000 | (run - 1));
+                run = 0;
             }
-            6 => {
-                match &*args[5] {
-                    "crossbeam" => {
-                        writeln!(std::io::stderr(), "selected render method is crossbeam").unwrap();
-                        render_by_crossbeam
-                    }
-                    "rayon" => {
-                        writeln!(std::io::stderr(), "selected render method is rayon").unwrap();
-                        render_by_rayon
-                    }
-                    "single" => {
-                        writeln!(std::io::stderr(), "selected render method is single").unwrap();
-                        render
+            prev = px;
+            continue;
+        }
+        if run > 0 {
+            out.push(0b11_000000 | (run - 1));
+            run = 0;
+        }
+
+        let index = qoi_hash(px);
+        if table[index] == px {
+            out.push(index as u8);
+        } else {
+            table[index] = px;
+
+            if px[3] != prev[3] {
+                out.push(0b11111111);
+                out.extend_from_slice(&px);
+            } else {
+                let dr = px[0].wrapping_sub(prev[0]) as i8;
+                let dg = px[1].wrapping_sub(prev[1]) as i8;
+                let db = px[2].wrapping_sub(prev[2]) as i8;
+
+                if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                    out.push(0b01_000000
+                        | (((dr + 2) as u8) << 4)
+                        | (((dg + 2) as u8) << 2)
+                        | (db + 2) as u8);
+                } else {
+                    let dr_dg = dr.wrapping_sub(dg);
+                    let db_dg = db.wrapping_sub(dg);
+                    if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                        out.push(0b10_000000 | (dg + 32) as u8);
+                        out.push((((dr_dg + 8) as u8) << 4) | (db_dg + 8) as u8);
+                    } else {
+                        out.push(0b11111110);
+                        out.push(px[0]);
+                        out.push(px[1]);
+                        out.push(px[2]);
                     }
-                    _ => {
-                        writeln!(std::io::stderr(), "no such RENDERMETHOD: {}", args[5]).unwrap();
-                        writeln!(std::io::stderr(), "RENDERMETHOD(single|crossbeam|rayon)").unwrap();
-                        help(args);
-                        std::process::exit(1);
+                }
+            }
+        }
+        prev = px;
+    }
+
+    out.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    out
+}
+
+/// Minimal QOI decoder used only to round-trip `qoi_encode` in tests; the
+/// encoder is the only direction the tool actually needs at runtime.
+#[cfg(test)]
+fn qoi_decode(data: &[u8]) -> ((u32, u32), Vec<u8>) {
+    let width = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    let height = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+    let pixel_count = (width * height) as usize;
+
+    let mut table = [[0u8; 4]; 64];
+    let mut prev = [0u8, 0, 0, 255];
+    let mut rgba = Vec::with_capacity(pixel_count * 4);
+    let mut pos = 14;
+
+    while rgba.len() < pixel_count * 4 {
+        let byte = data[pos];
+        pos += 1;
+        let px = if byte == 0b11111111 {
+            let px = [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]];
+            pos += 4;
+            px
+        } else if byte == 0b11111110 {
+            let px = [data[pos], data[pos + 1], data[pos + 2], prev[3]];
+            pos += 3;
+            px
+        } else {
+            match byte >> 6 {
+                0b00 => table[byte as usize & 0x3f],
+                0b01 => {
+                    let dr = ((byte >> 4) & 0x03) as i8 - 2;
+                    let dg = ((byte >> 2) & 0x03) as i8 - 2;
+                    let db = (byte & 0x03) as i8 - 2;
+                    [prev[0].wrapping_add(dr as u8), prev[1].wrapping_add(dg as u8), prev[2].wrapping_add(db as u8), prev[3]]
+                }
+                0b10 => {
+                    let dg = (byte & 0x3f) as i8 - 32;
+                    let second = data[pos];
+                    pos += 1;
+                    let dr_dg = ((second >> 4) & 0x0f) as i8 - 8;
+                    let db_dg = (second & 0x0f) as i8 - 8;
+                    [
+                        prev[0].wrapping_add(dg as u8).wrapping_add(dr_dg as u8),
+                        prev[1].wrapping_add(dg as u8),
+                        prev[2].wrapping_add(dg as u8).wrapping_add(db_dg as u8),
+                        prev[3],
+                    ]
+                }
+                _ /* 0b11 */ => {
+                    let run = (byte & 0x3f) + 1;
+                    for _ in 0 .. run {
+                        rgba.extend_from_slice(&prev);
                     }
+                    continue;
                 }
             }
-            _ => {
-                help(args);
-                std::process::exit(1);
+        };
+        table[qoi_hash(px)] = px;
+        rgba.extend_from_slice(&px);
+        prev = px;
+    }
+
+    ((width, height), rgba)
+}
+
+#[test]
+fn test_qoi_round_trip() {
+    let bounds = (4, 3);
+    let rgba: Vec<u8> = (0 .. bounds.0 * bounds.1)
+        .flat_map(|i| vec![(i * 17) as u8, (i * 31) as u8, (i * 53) as u8, 255])
+        .collect();
+
+    let encoded = qoi_encode(&rgba, bounds);
+    assert_eq!(&encoded[0..4], b"qoif");
+
+    let ((width, height), decoded) = qoi_decode(&encoded);
+    assert_eq!((width, height), (bounds.0 as u32, bounds.1 as u32));
+    assert_eq!(decoded, rgba);
+}
+
+#[test]
+fn test_qoi_round_trip_with_runs_and_repeats() {
+    // A palette with few distinct colors exercises the run-length and
+    // index-table opcodes, not just literals/diffs.
+    let bounds = (6, 1);
+    let rgba: Vec<u8> = vec![
+        10, 20, 30, 255,
+        10, 20, 30, 255,
+        10, 20, 30, 255,
+        0, 0, 0, 255,
+        10, 20, 30, 255,
+        200, 200, 200, 128,
+    ].into_iter().collect();
+
+    let encoded = qoi_encode(&rgba, bounds);
+    let (_, decoded) = qoi_decode(&encoded);
+    assert_eq!(decoded, rgba);
+}
+
+#[test]
+fn test_write_pnm_header_and_bytes() {
+    let path = std::env::temp_dir().join("mandelbrot_test_write_pnm.pgm");
+    let bounds = (2, 2);
+    let pixels = vec![0u8, 64, 128, 255];
+
+    write_pnm(path.to_str().unwrap(), &pixels, bounds, &ColorMode::Gray).unwrap();
+
+    let contents = std::fs::read(&path).unwrap();
+    assert_eq!(&contents, b"P5\n2 2\n255\n\x00\x40\x80\xff");
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Either a per-pixel render backend, or one of the histogram-accumulation
+/// paths (buddhabrot, nebulabrot), which work by tallying orbit visits
+/// rather than by coloring each pixel from its own escape time and so can't
+/// share the `fn(&mut [u8], ...)` signature above.
+#[derive(Debug)]
+enum RenderMethod {
+    Backend(fn(&mut [u8], (usize, usize), Complex<f64>, Complex<f64>, &FractalKind, &ColorMode)),
+    Buddhabrot,
+    Nebulabrot(u32, u32, u32),
+}
+
+/// Parses the `R,G,B` iteration limits out of a `nebulabrot:R,G,B`
+/// RENDERMETHOD token.
+fn parse_nebulabrot_limits(s: &str) -> Result<(u32, u32, u32), String> {
+    let rest = s.strip_prefix("nebulabrot:")
+        .ok_or_else(|| format!("expected 'nebulabrot:R,G,B', got '{}'", s))?;
+    let limits: Vec<&str> = rest.split(',').collect();
+    if let [r, g, b] = limits[..] {
+        let parse_limit = |limit: &str| limit.parse::<u32>()
+            .map_err(|_| format!("invalid iteration limit '{}' in '{}'", limit, s));
+        Ok((parse_limit(r)?, parse_limit(g)?, parse_limit(b)?))
+    } else {
+        Err(format!("expected three comma-separated iteration limits, got '{}'", rest))
+    }
+}
+
+/// Everything that can go wrong parsing arguments or producing output,
+/// reported with the offending input so the message is actionable without
+/// re-reading the usage string.
+#[derive(Error)]
+enum MandelError {
+    #[error("wrong number of arguments\nUsage: mandelbrot FILE PIXELS UPPERLEFT LOWERRIGHT RENDERMETHOD FRACTALKIND [--color|--gray] [--adaptive]\nExample: mandel.png 1280x960 -2.0,1 0.6,-1 rayon mandelbrot --color --adaptive\nRENDERMETHOD(single|crossbeam|rayon|buddhabrot|nebulabrot:R,G,B)")]
+    Usage,
+    #[error("error parsing image dimensions '{input}': {reason}")]
+    InvalidDimensions { input: String, reason: String },
+    #[error("error parsing complex number '{input}': {reason}")]
+    InvalidComplex { input: String, reason: String },
+    #[error("no such RENDERMETHOD '{0}' (expected single|crossbeam|rayon|buddhabrot|nebulabrot:R,G,B)")]
+    InvalidRenderMethod(String),
+    #[error("invalid fractal kind '{input}': {reason}")]
+    InvalidFractalKind { input: String, reason: String },
+    #[error("error writing image: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+// `main() -> Result<(), MandelError>` prints its Err via Debug, not Display;
+// delegate Debug to the #[error(...)] message so a failure reads as one
+// clear line instead of a derived struct dump.
+impl std::fmt::Debug for MandelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[derive(Debug)]
+struct Config {
+    filename: String,
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    render_method: RenderMethod,
+    fractal_kind: FractalKind,
+    mode: ColorMode,
+}
+
+fn parse_args(mut args: Vec<String>) -> Result<Config, MandelError> {
+    let mode = if let Some(pos) = args.iter().position(|a| a == "--color") {
+        args.remove(pos);
+        ColorMode::Color { period: DEFAULT_PALETTE_PERIOD }
+    } else if let Some(pos) = args.iter().position(|a| a == "--gray") {
+        args.remove(pos);
+        ColorMode::Gray
+    } else {
+        ColorMode::Gray
+    };
+
+    let adaptive = if let Some(pos) = args.iter().position(|a| a == "--adaptive") {
+        args.remove(pos);
+        true
+    } else {
+        false
+    };
+    let rayon_backend = if adaptive { render_by_rayon_adaptive } else { render_by_rayon };
+
+    if args.len() != 5 && args.len() != 6 && args.len() != 7 {
+        return Err(MandelError::Usage);
+    }
+
+    let render_method = match args.len() {
+        5 => RenderMethod::Backend(rayon_backend),
+        _ => match &*args[5] {
+            "crossbeam" => RenderMethod::Backend(render_by_crossbeam),
+            "rayon" => RenderMethod::Backend(rayon_backend),
+            "single" => RenderMethod::Backend(render),
+            "buddhabrot" => RenderMethod::Buddhabrot,
+            other if other.starts_with("nebulabrot:") => {
+                let (r, g, b) = parse_nebulabrot_limits(other)
+                    .map_err(|_| MandelError::InvalidRenderMethod(other.to_string()))?;
+                RenderMethod::Nebulabrot(r, g, b)
             }
+            other => return Err(MandelError::InvalidRenderMethod(other.to_string())),
         }
     };
+
     let bounds = parse_pair(&args[2], 'x')
-        .expect("error parsing image dimensions");
+        .map_err(|reason| MandelError::InvalidDimensions { input: args[2].clone(), reason })?;
     let upper_left = parse_complex(&args[3])
-        .expect("error parsing upper left corner point");
+        .map_err(|reason| MandelError::InvalidComplex { input: args[3].clone(), reason })?;
     let lower_right = parse_complex(&args[4])
-        .expect("error parsing lower right corner point");
+        .map_err(|reason| MandelError::InvalidComplex { input: args[4].clone(), reason })?;
+    let fractal_kind = match args.len() {
+        7 => FractalKind::from_str(&args[6])
+            .map_err(|reason| MandelError::InvalidFractalKind { input: args[6].clone(), reason })?,
+        _ => FractalKind::Mandelbrot
+    };
+
+    Ok(Config {
+        filename: args[1].clone(),
+        bounds,
+        upper_left,
+        lower_right,
+        render_method,
+        fractal_kind,
+        mode,
+    })
+}
+
+#[test]
+fn test_parse_args_wrong_arg_count_is_usage_error() {
+    let args = vec!["mandelbrot".to_string(), "out.png".to_string()];
+    match parse_args(args) {
+        Err(MandelError::Usage) => {}
+        other => panic!("expected Usage error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_args_invalid_dimensions() {
+    let args = vec![
+        "mandelbrot".to_string(), "out.png".to_string(), "not_a_size".to_string(),
+        "-2,1".to_string(), "0.6,-1".to_string(),
+    ];
+    match parse_args(args) {
+        Err(MandelError::InvalidDimensions { input, .. }) => assert_eq!(input, "not_a_size"),
+        other => panic!("expected InvalidDimensions, got {:?}", other),
+    }
+}
 
-    let mut pixels = vec![0; bounds.0 * bounds.1];
-    render_method(&mut pixels, bounds, upper_left, lower_right);
+#[test]
+fn test_parse_args_invalid_complex() {
+    let args = vec![
+        "mandelbrot".to_string(), "out.png".to_string(), "1280x960".to_string(),
+        "not_a_complex".to_string(), "0.6,-1".to_string(),
+    ];
+    match parse_args(args) {
+        Err(MandelError::InvalidComplex { input, .. }) => assert_eq!(input, "not_a_complex"),
+        other => panic!("expected InvalidComplex, got {:?}", other),
+    }
+}
 
-    write_image(&args[1], &pixels, bounds)
-        .expect("error writing PNG file");
+#[test]
+fn test_parse_args_invalid_render_method() {
+    let args = vec![
+        "mandelbrot".to_string(), "out.png".to_string(), "1280x960".to_string(),
+        "-2,1".to_string(), "0.6,-1".to_string(), "not_a_method".to_string(),
+    ];
+    match parse_args(args) {
+        Err(MandelError::InvalidRenderMethod(method)) => assert_eq!(method, "not_a_method"),
+        other => panic!("expected InvalidRenderMethod, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_args_invalid_fractal_kind() {
+    let args = vec![
+        "mandelbrot".to_string(), "out.png".to_string(), "1280x960".to_string(),
+        "-2,1".to_string(), "0.6,-1".to_string(), "rayon".to_string(), "not_a_kind".to_string(),
+    ];
+    match parse_args(args) {
+        Err(MandelError::InvalidFractalKind { input, .. }) => assert_eq!(input, "not_a_kind"),
+        other => panic!("expected InvalidFractalKind, got {:?}", other),
+    }
 }
 
+#[test]
+fn test_parse_args_valid_full_config() {
+    let args = vec![
+        "mandelbrot".to_string(), "out.png".to_string(), "1280x960".to_string(),
+        "-2,1".to_string(), "0.6,-1".to_string(), "nebulabrot:10,100,1000".to_string(),
+    ];
+    let config = parse_args(args).unwrap();
+    assert_eq!(config.filename, "out.png");
+    assert_eq!(config.bounds, (1280, 960));
+    match config.render_method {
+        RenderMethod::Nebulabrot(r, g, b) => assert_eq!((r, g, b), (10, 100, 1000)),
+        _ => panic!("expected Nebulabrot render method"),
+    }
+}
+
+fn run() -> Result<(), MandelError> {
+    let args: Vec<String> = std::env::args().collect();
+    let config = parse_args(args)?;
+
+    writeln!(std::io::stderr(), "rendering with {:?}, fractal kind {:?}", config.mode, config.fractal_kind).unwrap();
+
+    let (pixels, mode) = match config.render_method {
+        RenderMethod::Backend(backend) => {
+            let mut pixels = vec![0; config.bounds.0 * config.bounds.1 * config.mode.bytes_per_pixel()];
+            backend(&mut pixels, config.bounds, config.upper_left, config.lower_right, &config.fractal_kind, &config.mode);
+            (pixels, config.mode)
+        }
+        RenderMethod::Buddhabrot => {
+            let pixels = render_buddhabrot(config.bounds, config.upper_left, config.lower_right, BUDDHABROT_SAMPLES, BUDDHABROT_LIMIT);
+            (pixels, ColorMode::Gray)
+        }
+        RenderMethod::Nebulabrot(r, g, b) => {
+            let pixels = render_nebulabrot(config.bounds, config.upper_left, config.lower_right, BUDDHABROT_SAMPLES, (r, g, b));
+            (pixels, ColorMode::Color { period: DEFAULT_PALETTE_PERIOD })
+        }
+    };
+
+    write_image(&config.filename, &pixels, config.bounds, &mode)?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), MandelError> {
+    run()
+}